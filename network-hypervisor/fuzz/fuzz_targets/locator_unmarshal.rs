@@ -0,0 +1,31 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use network_hypervisor::vl1::buffer::Buffer;
+use network_hypervisor::vl1::protocol::PACKET_SIZE_MAX;
+use network_hypervisor::vl1::Locator;
+
+// Feeds arbitrary bytes to Locator::unmarshal. Anything that parses successfully must also
+// re-marshal to bytes that parse back to an equal Locator, so this also catches a round-trip
+// asymmetry between unmarshal and marshal.
+fuzz_target!(|data: &[u8]| {
+    if data.len() > PACKET_SIZE_MAX {
+        return;
+    }
+
+    let mut buf: Buffer<{ PACKET_SIZE_MAX }> = Buffer::new();
+    if buf.append_bytes(data).is_err() {
+        return;
+    }
+
+    let mut cursor = 0usize;
+    if let Ok(loc) = Locator::unmarshal(&buf, &mut cursor) {
+        let mut remarshaled: Buffer<{ PACKET_SIZE_MAX }> = Buffer::new();
+        let _ = loc.marshal(&mut remarshaled);
+
+        let mut cursor2 = 0usize;
+        let reparsed = Locator::unmarshal(&remarshaled, &mut cursor2).expect("re-marshaled locator must re-parse");
+        assert!(reparsed == loc, "locator did not round-trip stably");
+    }
+});
@@ -0,0 +1,28 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use network_hypervisor::vl1::buffer::Buffer;
+use network_hypervisor::vl1::protocol::PACKET_SIZE_MAX;
+use network_hypervisor::vl1::{Identity, Locator};
+
+// Feeds arbitrary bytes to Locator::unmarshal and then, for anything that parses, runs
+// verify_signature against a freshly generated identity. The interesting property here isn't
+// whether the signature is valid -- it almost never will be -- it's that verify_signature must
+// never panic on a Locator built from untrusted, likely-malformed input.
+fuzz_target!(|data: &[u8]| {
+    if data.len() > PACKET_SIZE_MAX {
+        return;
+    }
+
+    let mut buf: Buffer<{ PACKET_SIZE_MAX }> = Buffer::new();
+    if buf.append_bytes(data).is_err() {
+        return;
+    }
+
+    let mut cursor = 0usize;
+    if let Ok(loc) = Locator::unmarshal(&buf, &mut cursor) {
+        let identity = Identity::generate();
+        let _ = loc.verify_signature(&identity);
+    }
+});
@@ -0,0 +1,232 @@
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, SocketAddr};
+
+use crate::vl1::buffer::Buffer;
+
+/// Maximum length of a DNS hostname endpoint, per RFC 1035 (253 printable characters,
+/// excluding the trailing root label).
+const DNS_HOSTNAME_MAX_LEN: usize = 253;
+
+/// Discriminant written as the first byte of `Endpoint`'s own wire encoding. This is internal
+/// to `Endpoint::marshal`/`unmarshal` and distinct from the outer per-endpoint `network-id`
+/// tag that `Locator` wraps every `Endpoint` in: that tag says "this is a native vl1
+/// `Endpoint`", this byte says which variant of it.
+const ENDPOINT_TYPE_IP: u8 = 0;
+const ENDPOINT_TYPE_IP_UDP: u8 = 1;
+const ENDPOINT_TYPE_IP_TCP: u8 = 2;
+const ENDPOINT_TYPE_DNS: u8 = 3;
+
+/// A way a node may be reached.
+///
+/// Most endpoints are a bare IP or an IP plus transport, resolved and fixed at the time the
+/// locator listing them was signed. `Dns` is different: it carries a hostname and port to be
+/// resolved at connect time, so a node whose address changes doesn't need a new locator signed
+/// every time, as long as its DNS record is kept up to date.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    Ip(IpAddr),
+    IpUdp(SocketAddr),
+    IpTcp(SocketAddr),
+    Dns(String, u16),
+}
+
+impl Endpoint {
+    /// Create a `Dns` endpoint, validating the hostname up front rather than at marshal time.
+    ///
+    /// `hostname` must be non-empty, no longer than 253 characters, and contain only ASCII
+    /// letters, digits, `-`, and `.` -- the conventional hostname charset. This rejects
+    /// embedded nulls, whitespace, and other bytes that could confuse a resolver or be used to
+    /// smuggle something unexpected through what's supposed to be a plain hostname.
+    pub fn dns(hostname: String, port: u16) -> std::io::Result<Self> {
+        validate_hostname(&hostname)?;
+        Ok(Self::Dns(hostname, port))
+    }
+
+    fn type_id(&self) -> u8 {
+        match self {
+            Self::Ip(_) => ENDPOINT_TYPE_IP,
+            Self::IpUdp(_) => ENDPOINT_TYPE_IP_UDP,
+            Self::IpTcp(_) => ENDPOINT_TYPE_IP_TCP,
+            Self::Dns(_, _) => ENDPOINT_TYPE_DNS,
+        }
+    }
+
+    pub(crate) fn marshal<const BL: usize>(&self, buf: &mut Buffer<BL>) -> std::io::Result<()> {
+        buf.append_bytes(&[self.type_id()])?;
+        match self {
+            Self::Ip(ip) => marshal_ip(buf, ip)?,
+            Self::IpUdp(addr) | Self::IpTcp(addr) => {
+                marshal_ip(buf, &addr.ip())?;
+                buf.append_bytes(&addr.port().to_be_bytes())?;
+            }
+            Self::Dns(hostname, port) => {
+                validate_hostname(hostname)?;
+                let hostname_bytes = hostname.as_bytes();
+                buf.append_varint(hostname_bytes.len() as u64)?;
+                buf.append_bytes(hostname_bytes)?;
+                buf.append_bytes(&port.to_be_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn unmarshal<const BL: usize>(buf: &Buffer<BL>, cursor: &mut usize) -> std::io::Result<Self> {
+        let type_id = buf.read_bytes(1, cursor)?[0];
+        match type_id {
+            ENDPOINT_TYPE_IP => Ok(Self::Ip(unmarshal_ip(buf, cursor)?)),
+            ENDPOINT_TYPE_IP_UDP => Ok(Self::IpUdp(unmarshal_socket_addr(buf, cursor)?)),
+            ENDPOINT_TYPE_IP_TCP => Ok(Self::IpTcp(unmarshal_socket_addr(buf, cursor)?)),
+            ENDPOINT_TYPE_DNS => {
+                let len = buf.read_varint(cursor)? as usize;
+                if len > DNS_HOSTNAME_MAX_LEN {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "DNS hostname exceeds maximum length"));
+                }
+                let hostname_bytes = buf.read_bytes(len, cursor)?;
+                let hostname = std::str::from_utf8(hostname_bytes)
+                    .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "DNS hostname is not valid UTF-8"))?
+                    .to_string();
+                validate_hostname(&hostname)?;
+                let port_bytes = buf.read_bytes(2, cursor)?;
+                let port = u16::from_be_bytes([port_bytes[0], port_bytes[1]]);
+                Ok(Self::Dns(hostname, port))
+            }
+            _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "unrecognized endpoint type")),
+        }
+    }
+}
+
+/// Validate a `Dns` endpoint's hostname: non-empty, within the RFC 1035 length limit, and
+/// restricted to the conventional hostname charset (ASCII letters, digits, `-`, `.`).
+fn validate_hostname(hostname: &str) -> std::io::Result<()> {
+    if hostname.is_empty() || hostname.len() > DNS_HOSTNAME_MAX_LEN {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "DNS hostname length out of range"));
+    }
+    if !hostname.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.') {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "DNS hostname contains an invalid character"));
+    }
+    Ok(())
+}
+
+fn marshal_ip<const BL: usize>(buf: &mut Buffer<BL>, ip: &IpAddr) -> std::io::Result<()> {
+    match ip {
+        IpAddr::V4(v4) => {
+            buf.append_bytes(&[4])?;
+            buf.append_bytes(&v4.octets())?;
+        }
+        IpAddr::V6(v6) => {
+            buf.append_bytes(&[6])?;
+            buf.append_bytes(&v6.octets())?;
+        }
+    }
+    Ok(())
+}
+
+fn unmarshal_ip<const BL: usize>(buf: &Buffer<BL>, cursor: &mut usize) -> std::io::Result<IpAddr> {
+    let family = buf.read_bytes(1, cursor)?[0];
+    match family {
+        4 => {
+            let b = buf.read_bytes(4, cursor)?;
+            Ok(IpAddr::from([b[0], b[1], b[2], b[3]]))
+        }
+        6 => {
+            let b = buf.read_bytes(16, cursor)?;
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(b);
+            Ok(IpAddr::from(octets))
+        }
+        _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "unrecognized IP address family")),
+    }
+}
+
+fn unmarshal_socket_addr<const BL: usize>(buf: &Buffer<BL>, cursor: &mut usize) -> std::io::Result<SocketAddr> {
+    let ip = unmarshal_ip(buf, cursor)?;
+    let port_bytes = buf.read_bytes(2, cursor)?;
+    let port = u16::from_be_bytes([port_bytes[0], port_bytes[1]]);
+    Ok(SocketAddr::new(ip, port))
+}
+
+impl Ord for Endpoint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Ip(a), Self::Ip(b)) => a.cmp(b),
+            (Self::IpUdp(a), Self::IpUdp(b)) | (Self::IpTcp(a), Self::IpTcp(b)) => (a.ip(), a.port()).cmp(&(b.ip(), b.port())),
+            (Self::Dns(ah, ap), Self::Dns(bh, bp)) => (ah, ap).cmp(&(bh, bp)),
+            (a, b) => a.type_id().cmp(&b.type_id()),
+        }
+    }
+}
+
+impl PartialOrd for Endpoint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Hash for Endpoint {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u8(self.type_id());
+        match self {
+            Self::Ip(ip) => ip.hash(state),
+            Self::IpUdp(addr) | Self::IpTcp(addr) => addr.hash(state),
+            Self::Dns(hostname, port) => {
+                hostname.hash(state);
+                port.hash(state);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod endpoint_tests {
+    use super::*;
+    use crate::vl1::protocol::PACKET_SIZE_MAX;
+
+    fn round_trip(e: &Endpoint) -> Endpoint {
+        let mut buf: Buffer<{ PACKET_SIZE_MAX }> = Buffer::new();
+        e.marshal(&mut buf).unwrap();
+        let mut cursor = 0usize;
+        Endpoint::unmarshal(&buf, &mut cursor).unwrap()
+    }
+
+    #[test]
+    fn ip_endpoint_round_trips() {
+        let e = Endpoint::Ip("203.0.113.7".parse().unwrap());
+        assert_eq!(round_trip(&e), e);
+        let e = Endpoint::Ip("2001:db8::1".parse().unwrap());
+        assert_eq!(round_trip(&e), e);
+    }
+
+    #[test]
+    fn ip_udp_and_ip_tcp_endpoints_round_trip() {
+        let e = Endpoint::IpUdp("203.0.113.7:9993".parse().unwrap());
+        assert_eq!(round_trip(&e), e);
+        let e = Endpoint::IpTcp("[2001:db8::1]:443".parse().unwrap());
+        assert_eq!(round_trip(&e), e);
+    }
+
+    #[test]
+    fn dns_endpoint_round_trips() {
+        let e = Endpoint::dns("roots.example.com".to_string(), 9993).unwrap();
+        assert_eq!(round_trip(&e), e);
+    }
+
+    #[test]
+    fn dns_endpoint_rejects_invalid_hostnames() {
+        assert!(Endpoint::dns(String::new(), 9993).is_err(), "empty hostname must be rejected");
+        assert!(Endpoint::dns("a".repeat(DNS_HOSTNAME_MAX_LEN + 1), 9993).is_err(), "oversized hostname must be rejected");
+        assert!(Endpoint::dns("not a hostname!".to_string(), 9993).is_err(), "hostname with invalid characters must be rejected");
+        assert!(Endpoint::dns("roots.example.com".to_string(), 9993).is_ok());
+    }
+
+    #[test]
+    fn unmarshal_rejects_a_hostname_length_claim_that_exceeds_the_limit() {
+        // Hand-build a buffer claiming a hostname far longer than DNS_HOSTNAME_MAX_LEN allows,
+        // to confirm unmarshal enforces the limit itself rather than trusting marshal's caller.
+        let mut buf: Buffer<{ PACKET_SIZE_MAX }> = Buffer::new();
+        buf.append_bytes(&[ENDPOINT_TYPE_DNS]).unwrap();
+        buf.append_varint((DNS_HOSTNAME_MAX_LEN as u64) + 1).unwrap();
+        let mut cursor = 0usize;
+        assert!(Endpoint::unmarshal(&buf, &mut cursor).is_err());
+    }
+}
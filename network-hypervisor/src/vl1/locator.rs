@@ -5,6 +5,17 @@ use crate::vl1::{Address, Endpoint, Identity};
 use crate::vl1::buffer::Buffer;
 use crate::vl1::protocol::PACKET_SIZE_MAX;
 
+/// Network/address-family tag for the self-describing per-endpoint encoding used by
+/// `marshal_internal`/`unmarshal` (modeled on Bitcoin's `AddrV2Message` scheme). This is
+/// currently the only tag in use: it wraps the entire `vl1::endpoint::Endpoint` enum,
+/// including the `Dns` hostname variant, and `Endpoint`'s own internal discriminant (see
+/// `Endpoint::marshal`) is what distinguishes the address classes carried inside it. A
+/// genuinely new transport that isn't representable as an `Endpoint` at all (e.g. Tor v3
+/// onion or I2P, which don't fit the IP/hostname shape `Endpoint` assumes) would need its own
+/// tag here instead. Tags this version doesn't recognize are skipped by length rather than
+/// rejected, so a locator carrying one still parses on this version.
+const ENDPOINT_NETWORK_ID_NATIVE: u8 = 0;
+
 /// A signed object generated by nodes to inform the network where they may be found.
 ///
 /// By default this will just enumerate the roots used by this node, but nodes with
@@ -96,7 +107,14 @@ impl Locator {
         buf.append_u64(self.timestamp as u64)?;
         buf.append_varint(self.endpoints.len() as u64)?;
         for e in self.endpoints.iter() {
-            e.marshal(buf)?;
+            // Each endpoint is wrapped in a self-describing <network-id><varint length><bytes>
+            // envelope (AddrV2-style, see `unmarshal`) so that endpoint classes added in the
+            // future can be carried here without breaking parsers that predate them.
+            let mut eb: Buffer<BL> = Buffer::new();
+            e.marshal(&mut eb)?;
+            buf.append_bytes(&[ENDPOINT_NETWORK_ID_NATIVE])?;
+            buf.append_varint(eb.as_bytes().len() as u64)?;
+            buf.append_bytes(eb.as_bytes())?;
         }
         buf.append_varint(0)?; // length of any additional fields
         if !exclude_signature {
@@ -106,24 +124,65 @@ impl Locator {
         Ok(())
     }
 
+    // Both of these are `pub` (rather than `pub(crate)`) so that the wire parser can be
+    // exercised directly by the fuzz targets under `fuzz/`, which sit in their own crate.
+
     #[inline(always)]
-    pub(crate) fn marshal<const BL: usize>(&self, buf: &mut Buffer<BL>) -> std::io::Result<()> { self.marshal_internal(buf, false) }
+    pub fn marshal<const BL: usize>(&self, buf: &mut Buffer<BL>) -> std::io::Result<()> { self.marshal_internal(buf, false) }
 
-    pub(crate) fn unmarshal<const BL: usize>(buf: &Buffer<BL>, cursor: &mut usize) -> std::io::Result<Self> {
+    pub fn unmarshal<const BL: usize>(buf: &Buffer<BL>, cursor: &mut usize) -> std::io::Result<Self> {
         let subject = Address::unmarshal(buf, cursor)?;
         let signer = Address::unmarshal(buf, cursor)?;
         if subject.is_none() || signer.is_none() {
             return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid subject or signer address"));
         }
         let timestamp = buf.read_u64(cursor)? as i64;
+
+        // `endpoint_count` is untrusted and must never drive a speculative allocation larger
+        // than this buffer could actually contain: every endpoint takes at least a network-id
+        // byte plus a one-byte length varint, so it can never legitimately exceed the bytes
+        // remaining, and it's additionally capped to the overall packet size sanity limit.
         let endpoint_count = buf.read_varint(cursor)? as usize;
-        let mut endpoints: Vec<Endpoint> = Vec::new();
+        if endpoint_count > PACKET_SIZE_MAX || endpoint_count > Self::remaining(buf, *cursor) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "endpoint count exceeds remaining buffer size"));
+        }
+        let mut endpoints: Vec<Endpoint> = Vec::with_capacity(endpoint_count);
         for _ in 0..endpoint_count {
-            endpoints.push(Endpoint::unmarshal(buf, cursor)?);
+            let network_id = buf.read_bytes(1, cursor)?[0];
+            let len = buf.read_varint(cursor)? as usize;
+            if len > Self::remaining(buf, *cursor) {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "endpoint length exceeds remaining buffer size"));
+            }
+            match network_id {
+                ENDPOINT_NETWORK_ID_NATIVE => {
+                    let start = *cursor;
+                    endpoints.push(Endpoint::unmarshal(buf, cursor)?);
+                    if *cursor - start != len {
+                        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "endpoint length did not match declared length"));
+                    }
+                }
+                _ => {
+                    // Unrecognized network-id (e.g. a Tor v3 onion or I2P endpoint added by a
+                    // newer version, carried under its own tag rather than as an `Endpoint`):
+                    // skip over its declared length rather than rejecting the whole locator, so
+                    // this remains forward compatible.
+                    *cursor += len;
+                }
+            }
         }
-        *cursor += buf.read_varint(cursor)? as usize;
+
+        let trailing_field_len = buf.read_varint(cursor)? as usize;
+        if trailing_field_len > Self::remaining(buf, *cursor) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "trailing field length exceeds remaining buffer size"));
+        }
+        *cursor += trailing_field_len;
+
         let signature_len = buf.read_varint(cursor)? as usize;
+        if signature_len > PACKET_SIZE_MAX || signature_len > Self::remaining(buf, *cursor) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "signature length exceeds remaining buffer size"));
+        }
         let signature = buf.read_bytes(signature_len, cursor)?;
+
         Ok(Locator {
             subject: subject.unwrap(),
             signer: signer.unwrap(),
@@ -132,6 +191,13 @@ impl Locator {
             signature: signature.to_vec(),
         })
     }
+
+    /// Bytes remaining in `buf` at and after `cursor`, used to validate an attacker-controlled
+    /// length/count before it's used to size an allocation or skip.
+    #[inline(always)]
+    fn remaining<const BL: usize>(buf: &Buffer<BL>, cursor: usize) -> usize {
+        buf.as_bytes().len().saturating_sub(cursor)
+    }
 }
 
 impl Ord for Locator {
@@ -1,7 +1,9 @@
 // (c) 2020-2022 ZeroTier, Inc. -- currently propritery pending actual release and licensing. See LICENSE.md.
 
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
 use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, Ipv6Addr};
 use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
 
 use lazy_static::lazy_static;
@@ -20,8 +22,277 @@ pub(crate) enum PathServiceResult {
     NeedsKeepalive,
 }
 
+/// How backed-off the keepalive interval is allowed to get before we give up and let the path
+/// expire on its own via `PATH_EXPIRATION_TIME`.
+const PATH_KEEPALIVE_INTERVAL_MAX: i64 = PATH_KEEPALIVE_INTERVAL * 8;
+/// Consecutive unanswered keepalives after which a path is downgraded from probationary
+/// (`TimeoutAwaitingPong`) to `WasGood`, i.e. no longer preferred over a never-tested path.
+const PATH_WAS_GOOD_FAILURE_THRESHOLD: u32 = 3;
+
+/// Reachability state of a `Path`, modeled after the multi-state address-reachability
+/// tracking used by DNS seeder crawlers: paths degrade gradually through probationary states
+/// rather than flipping straight from "fine" to "dead", so a node with more than one path to
+/// a peer can prefer a `Good` path over one that's merely `Untested` or recovering.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PathState {
+    /// No keepalive round-trip has completed on this path yet.
+    Untested,
+    /// A keepalive (or any traffic) was received recently; this path is known-good.
+    Good,
+    /// This path was `Good` at some point but has since missed enough keepalives to fall out
+    /// of the `TimeoutAwaitingPong` probation window without yet being considered dead.
+    WasGood,
+    /// A keepalive was just sent and we're waiting to hear back; still within tolerance.
+    TimeoutAwaitingPong,
+    /// Keepalives have gone unanswered long enough that this path should not be preferred.
+    Timeout,
+    /// This path sent traffic that violated protocol expectations (e.g. malformed or
+    /// out-of-context packets) and should be avoided independent of keepalive timing.
+    ProtocolViolation,
+}
+
+struct PathHealth {
+    state: PathState,
+    consecutive_keepalive_failures: u32,
+    last_confirmed_alive_ticks: i64,
+    next_keepalive_interval: i64,
+}
+
+impl PathHealth {
+    fn new() -> Self {
+        Self { state: PathState::Untested, consecutive_keepalive_failures: 0, last_confirmed_alive_ticks: crate::util::NEVER_HAPPENED_TICKS, next_keepalive_interval: PATH_KEEPALIVE_INTERVAL }
+    }
+
+    /// Record that traffic of any kind was just received. Resets the keepalive backoff and
+    /// pulls the state back to `Good` from any keepalive-timeout degradation, but deliberately
+    /// leaves `ProtocolViolation` alone: it's tracked independently of keepalive timing, and
+    /// clobbering it here on the very next packet -- valid or not -- would make
+    /// `flag_protocol_violation` a no-op. The only thing that moves a path out of that state is
+    /// `service()` marking an expired path `Timeout`; its keepalive backoff leaves it as is.
+    fn note_traffic_received(&mut self, time_ticks: i64) {
+        if self.state != PathState::ProtocolViolation {
+            self.state = PathState::Good;
+        }
+        self.consecutive_keepalive_failures = 0;
+        self.next_keepalive_interval = PATH_KEEPALIVE_INTERVAL;
+        self.last_confirmed_alive_ticks = time_ticks;
+    }
+}
+
+#[cfg(test)]
+mod path_health_tests {
+    use super::*;
+
+    #[test]
+    fn traffic_received_brings_an_untested_path_to_good() {
+        let mut h = PathHealth::new();
+        h.note_traffic_received(100);
+        assert_eq!(h.state, PathState::Good);
+        assert_eq!(h.last_confirmed_alive_ticks, 100);
+    }
+
+    #[test]
+    fn traffic_received_recovers_a_path_degraded_by_missed_keepalives() {
+        let mut h = PathHealth::new();
+        h.state = PathState::TimeoutAwaitingPong;
+        h.consecutive_keepalive_failures = 2;
+        h.note_traffic_received(200);
+        assert_eq!(h.state, PathState::Good);
+        assert_eq!(h.consecutive_keepalive_failures, 0);
+        assert_eq!(h.next_keepalive_interval, PATH_KEEPALIVE_INTERVAL);
+    }
+
+    /// Pins the bug the maintainer found: receiving any further traffic must not silently
+    /// clear a protocol violation flag. Only `service()` marking an expired path `Timeout`
+    /// moves a path out of `ProtocolViolation`.
+    #[test]
+    fn traffic_received_does_not_clear_a_protocol_violation() {
+        let mut h = PathHealth::new();
+        h.state = PathState::ProtocolViolation;
+        h.note_traffic_received(300);
+        assert_eq!(h.state, PathState::ProtocolViolation);
+        // The rest of the bookkeeping still proceeds normally -- only the state is pinned.
+        assert_eq!(h.last_confirmed_alive_ticks, 300);
+        assert_eq!(h.consecutive_keepalive_failures, 0);
+    }
+}
+
 lazy_static! {
     static ref INSTANCE_ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
+    static ref UNAUTHENTICATED_SOURCE_RATE_LIMITER: SourceRateLimiter = SourceRateLimiter::new();
+}
+
+/// One token bucket refill every 100ms, up to a burst of 5 outstanding reassembly/
+/// unauthenticated-packet events per source.
+const RATE_LIMITER_REFILL_INTERVAL_MS: i64 = 100;
+const RATE_LIMITER_BURST: u32 = 5;
+/// A bucket that hasn't been touched in this long is considered stale and is dropped the next
+/// time garbage collection runs, so the table can't grow unbounded under a distributed flood.
+const RATE_LIMITER_STALE_MS: i64 = 10_000;
+/// How often `service()` bothers sweeping the bucket table for stale entries.
+const RATE_LIMITER_GC_INTERVAL_MS: i64 = 30_000;
+
+/// Collapse a source IP down to its rate-limiting key: the address itself for IPv4, or the
+/// containing /64 for IPv6, so that rotating within a subnet doesn't bypass the limiter.
+fn rate_limit_key(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(v6) => {
+            let mut segments = v6.segments();
+            segments[4] = 0;
+            segments[5] = 0;
+            segments[6] = 0;
+            segments[7] = 0;
+            IpAddr::V6(Ipv6Addr::from(segments))
+        }
+    }
+}
+
+/// Extract the source IP a path's endpoint is reachable at, if it has one. Endpoint types
+/// with no notion of an IP address (e.g. relayed or non-IP transports) are exempt from this
+/// limiter, since they're already gated by some other authentication mechanism.
+fn endpoint_source_ip(endpoint: &Endpoint) -> Option<IpAddr> {
+    match endpoint {
+        Endpoint::Ip(ip) | Endpoint::IpUdp(ip) | Endpoint::IpTcp(ip) => ip.to_ipaddr(),
+        _ => None,
+    }
+}
+
+struct RateLimiterBucket {
+    tokens: u32,
+    last_refill_ticks: i64,
+    last_used_ticks: i64,
+}
+
+/// A token-bucket rate limiter keyed by source address, modeled on WireGuard's handshake
+/// rate limiter. Used to throttle how fast a single source can initiate new fragment
+/// reassembly entries or other unauthenticated-packet-triggered work.
+struct SourceRateLimiter {
+    buckets: Mutex<HashMap<IpAddr, RateLimiterBucket>>,
+    last_gc_ticks: AtomicI64,
+}
+
+impl SourceRateLimiter {
+    fn new() -> Self {
+        Self { buckets: Mutex::new(HashMap::new()), last_gc_ticks: AtomicI64::new(crate::util::NEVER_HAPPENED_TICKS) }
+    }
+
+    /// Returns true if a new event from `key` is allowed right now, consuming a token if so.
+    fn allow(&self, key: IpAddr, time_ticks: i64) -> bool {
+        let mut buckets = self.buckets.lock();
+        let bucket = buckets.entry(key).or_insert_with(|| RateLimiterBucket { tokens: RATE_LIMITER_BURST, last_refill_ticks: time_ticks, last_used_ticks: time_ticks });
+
+        let elapsed = time_ticks - bucket.last_refill_ticks;
+        if elapsed >= RATE_LIMITER_REFILL_INTERVAL_MS {
+            // Clamp the computed refill to the burst cap *before* adding it to the existing
+            // token count: a bucket that's gone untouched for a very long time (a stalled or
+            // suspended process, a clock jump) can make `elapsed / REFILL_INTERVAL` far larger
+            // than u32 arithmetic should add unclamped, and clamping only after the add would
+            // let that addition overflow first.
+            let refill = (elapsed / RATE_LIMITER_REFILL_INTERVAL_MS).min(RATE_LIMITER_BURST as i64) as u32;
+            bucket.tokens = bucket.tokens.saturating_add(refill).min(RATE_LIMITER_BURST);
+            bucket.last_refill_ticks = time_ticks;
+        }
+        bucket.last_used_ticks = time_ticks;
+
+        if bucket.tokens >= 1 {
+            bucket.tokens -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Run garbage collection if it's been at least `RATE_LIMITER_GC_INTERVAL_MS` since the
+    /// last sweep, evicting buckets that have gone quiet.
+    fn garbage_collect_if_due(&self, time_ticks: i64) {
+        let last = self.last_gc_ticks.load(Ordering::Relaxed);
+        if (time_ticks - last) >= RATE_LIMITER_GC_INTERVAL_MS && self.last_gc_ticks.compare_exchange(last, time_ticks, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+            self.buckets.lock().retain(|_, b| (time_ticks - b.last_used_ticks) < RATE_LIMITER_STALE_MS);
+        }
+    }
+}
+
+#[cfg(test)]
+mod rate_limit_tests {
+    use super::*;
+
+    #[test]
+    fn ipv6_sources_collapse_to_a_shared_64_prefix() {
+        let a: IpAddr = "2001:db8:1234:5678:aaaa:bbbb:cccc:dddd".parse().unwrap();
+        let b: IpAddr = "2001:db8:1234:5678:1111:2222:3333:4444".parse().unwrap();
+        assert_eq!(rate_limit_key(a), rate_limit_key(b));
+
+        let different_prefix: IpAddr = "2001:db8:1234:9999::1".parse().unwrap();
+        assert_ne!(rate_limit_key(a), rate_limit_key(different_prefix));
+    }
+
+    #[test]
+    fn ipv4_sources_are_keyed_as_is() {
+        let a: IpAddr = "203.0.113.7".parse().unwrap();
+        assert_eq!(rate_limit_key(a), a);
+    }
+
+    #[test]
+    fn burst_is_exhausted_then_blocks() {
+        let rl = SourceRateLimiter::new();
+        let key: IpAddr = "203.0.113.7".parse().unwrap();
+        for _ in 0..RATE_LIMITER_BURST {
+            assert!(rl.allow(key, 0));
+        }
+        assert!(!rl.allow(key, 0));
+    }
+
+    #[test]
+    fn tokens_refill_over_time_but_never_past_the_burst_cap() {
+        let rl = SourceRateLimiter::new();
+        let key: IpAddr = "203.0.113.7".parse().unwrap();
+        for _ in 0..RATE_LIMITER_BURST {
+            assert!(rl.allow(key, 0));
+        }
+        assert!(!rl.allow(key, RATE_LIMITER_REFILL_INTERVAL_MS - 1), "must not refill before a full interval elapses");
+        assert!(rl.allow(key, RATE_LIMITER_REFILL_INTERVAL_MS), "one interval must refill exactly one token");
+        assert!(!rl.allow(key, RATE_LIMITER_REFILL_INTERVAL_MS));
+
+        // An enormous gap (far more intervals than the burst could ever hold) must still only
+        // refill up to the burst cap, not overflow or over-refill.
+        let far_future = RATE_LIMITER_REFILL_INTERVAL_MS * 1_000_000;
+        for _ in 0..RATE_LIMITER_BURST {
+            assert!(rl.allow(key, far_future));
+        }
+        assert!(!rl.allow(key, far_future));
+    }
+
+    #[test]
+    fn distinct_keys_have_independent_buckets() {
+        let rl = SourceRateLimiter::new();
+        let a: IpAddr = "203.0.113.7".parse().unwrap();
+        let b: IpAddr = "203.0.113.8".parse().unwrap();
+        for _ in 0..RATE_LIMITER_BURST {
+            assert!(rl.allow(a, 0));
+        }
+        assert!(!rl.allow(a, 0));
+        assert!(rl.allow(b, 0), "a different key must not be affected by another key's exhausted bucket");
+    }
+
+    #[test]
+    fn garbage_collection_evicts_only_stale_buckets() {
+        // Constructed directly (rather than via `new()`) so the GC baseline is a known tick
+        // value instead of `crate::util::NEVER_HAPPENED_TICKS`, which this test has no need to
+        // depend on.
+        let rl = SourceRateLimiter { buckets: Mutex::new(HashMap::new()), last_gc_ticks: AtomicI64::new(0) };
+        let stale: IpAddr = "203.0.113.7".parse().unwrap();
+        let fresh: IpAddr = "203.0.113.8".parse().unwrap();
+        assert!(rl.allow(stale, 0));
+
+        let gc_time = RATE_LIMITER_GC_INTERVAL_MS;
+        assert!(rl.allow(fresh, gc_time));
+        rl.garbage_collect_if_due(gc_time);
+
+        let buckets = rl.buckets.lock();
+        assert!(!buckets.contains_key(&stale), "a bucket untouched for RATE_LIMITER_STALE_MS should be evicted");
+        assert!(buckets.contains_key(&fresh), "a recently touched bucket should survive garbage collection");
+    }
 }
 
 /// A remote endpoint paired with a local socket and a local interface.
@@ -36,7 +307,9 @@ pub struct Path<SI: SystemInterface> {
     last_send_time_ticks: AtomicI64,
     last_receive_time_ticks: AtomicI64,
     create_time_ticks: i64,
-    fragmented_packets: Mutex<HashMap<PacketId, FragmentedPacket, PacketIdHasher>>,
+    fragmented_packets: Mutex<FragmentReassemblyTable>,
+    anti_replay_window: Mutex<AntiReplayWindow>,
+    health: Mutex<PathHealth>,
 }
 
 impl<SI: SystemInterface> Path<SI> {
@@ -49,40 +322,88 @@ impl<SI: SystemInterface> Path<SI> {
             last_send_time_ticks: AtomicI64::new(crate::util::NEVER_HAPPENED_TICKS),
             last_receive_time_ticks: AtomicI64::new(crate::util::NEVER_HAPPENED_TICKS),
             create_time_ticks: time_ticks,
-            fragmented_packets: Mutex::new(HashMap::with_capacity_and_hasher(4, PacketIdHasher(zerotier_core_crypto::random::xorshift64_random()))),
+            fragmented_packets: Mutex::new(FragmentReassemblyTable::new(zerotier_core_crypto::random::xorshift64_random())),
+            anti_replay_window: Mutex::new(AntiReplayWindow::new()),
+            health: Mutex::new(PathHealth::new()),
         }
     }
 
+    /// Current reachability state of this path. Prefer `Good` paths over probationary or
+    /// untested ones when more than one path to a peer is available.
+    #[inline(always)]
+    pub fn state(&self) -> PathState {
+        self.health.lock().state
+    }
+
+    /// Number of consecutive keepalives that have gone unanswered on this path.
+    #[inline(always)]
+    pub fn consecutive_keepalive_failures(&self) -> u32 {
+        self.health.lock().consecutive_keepalive_failures
+    }
+
+    /// Tick at which this path was last confirmed alive (i.e. the last time any traffic was
+    /// received on it), or `NEVER_HAPPENED_TICKS` if it never has been.
+    #[inline(always)]
+    pub fn last_confirmed_alive_ticks(&self) -> i64 {
+        self.health.lock().last_confirmed_alive_ticks
+    }
+
+    /// Flag this path as having exhibited a protocol anomaly (a malformed or out-of-context
+    /// packet, for example). This is tracked independently of keepalive timeouts so a path
+    /// that is otherwise timely can still be avoided.
+    pub(crate) fn flag_protocol_violation(&self) {
+        self.health.lock().state = PathState::ProtocolViolation;
+    }
+
+    /// Check and record a packet's counter (its `PacketId`) against this path's sliding
+    /// anti-replay window, rejecting anything that is a duplicate or too old to fall within
+    /// the window at all.
+    ///
+    /// This should be called once per inbound packet on this path, after authentication but
+    /// before the packet's payload is acted upon. The first counter ever seen on a path is
+    /// always accepted, since a freshly canonicalized path has nothing to replay against yet.
+    pub(crate) fn validate_packet_counter(&self, counter: u64) -> bool {
+        self.anti_replay_window.lock().check_and_update(counter)
+    }
+
     /// Receive a fragment and return a FragmentedPacket if the entire packet was assembled.
     /// This returns None if more fragments are needed to assemble the packet.
     pub(crate) fn receive_fragment(&self, packet_id: PacketId, fragment_no: u8, fragment_expecting_count: u8, packet: PooledPacketBuffer, time_ticks: i64) -> Option<FragmentedPacket> {
         let mut fp = self.fragmented_packets.lock();
 
-        // Discard some old waiting packets if the total incoming fragments for a path exceeds a
-        // sanity limit. This is to prevent memory exhaustion DOS attacks.
-        let fps = fp.len();
-        if fps > packet_constants::FRAGMENT_MAX_INBOUND_PACKETS_PER_PATH {
-            let mut entries: Vec<(i64, u64)> = Vec::new();
-            entries.reserve(fps);
-            for f in fp.iter() {
-                entries.push((f.1.ts_ticks, *f.0));
+        if !fp.contains_key(&packet_id) {
+            // This is a new, not-yet-seen packet ID: it's about to initiate a new reassembly
+            // entry, so it's subject to this source's rate limit. Rejecting here means the
+            // fragment never touches the reassembly map at all.
+            if !self.rate_limit_new_packet(time_ticks) {
+                return None;
             }
-            entries.sort_unstable_by(|a, b| (*a).0.cmp(&(*b).0));
-            for i in 0..(fps / 3) {
-                let _ = fp.remove(&(*entries.get(i).unwrap()).1);
+
+            // Discard some old waiting packets if the total incoming fragments for a path exceeds a
+            // sanity limit. This is to prevent memory exhaustion DOS attacks.
+            let fps = fp.len();
+            if fps > packet_constants::FRAGMENT_MAX_INBOUND_PACKETS_PER_PATH {
+                fp.evict_oldest(fps / 3);
             }
         }
 
-        if fp.entry(packet_id).or_insert_with(|| FragmentedPacket::new(time_ticks)).add_fragment(packet, fragment_no, fragment_expecting_count) {
+        if fp.get_or_insert(packet_id, time_ticks).add_fragment(packet, fragment_no, fragment_expecting_count) {
             fp.remove(&packet_id)
         } else {
             None
         }
     }
 
-    #[inline(always)]
+    /// Returns true if a new fragment-reassembly / unauthenticated-packet event originating
+    /// from this path's source address is currently allowed under the global token-bucket rate
+    /// limiter, false if the source is currently throttled and the event should be dropped.
+    fn rate_limit_new_packet(&self, time_ticks: i64) -> bool {
+        endpoint_source_ip(&self.endpoint).map_or(true, |ip| UNAUTHENTICATED_SOURCE_RATE_LIMITER.allow(rate_limit_key(ip), time_ticks))
+    }
+
     pub(crate) fn log_receive_anything(&self, time_ticks: i64) {
         self.last_receive_time_ticks.store(time_ticks, Ordering::Relaxed);
+        self.health.lock().note_traffic_received(time_ticks);
     }
 
     #[inline(always)]
@@ -91,9 +412,27 @@ impl<SI: SystemInterface> Path<SI> {
     }
 
     pub(crate) fn service(&self, time_ticks: i64) -> PathServiceResult {
-        self.fragmented_packets.lock().retain(|_, frag| (time_ticks - frag.ts_ticks) < packet_constants::FRAGMENT_EXPIRATION);
-        if (time_ticks - self.last_receive_time_ticks.load(Ordering::Relaxed)) < PATH_EXPIRATION_TIME {
-            if (time_ticks - self.last_send_time_ticks.load(Ordering::Relaxed)) >= PATH_KEEPALIVE_INTERVAL {
+        self.fragmented_packets.lock().expire(time_ticks);
+        UNAUTHENTICATED_SOURCE_RATE_LIMITER.garbage_collect_if_due(time_ticks);
+
+        let last_receive = self.last_receive_time_ticks.load(Ordering::Relaxed);
+        let last_send = self.last_send_time_ticks.load(Ordering::Relaxed);
+
+        if (time_ticks - last_receive) < PATH_EXPIRATION_TIME {
+            let mut health = self.health.lock();
+            if (time_ticks - last_send) >= health.next_keepalive_interval {
+                // A keepalive sent a full interval ago with no traffic received since counts
+                // as unanswered: back off and degrade the path's state.
+                if last_send > crate::util::NEVER_HAPPENED_TICKS && last_receive < last_send {
+                    health.consecutive_keepalive_failures += 1;
+                    health.next_keepalive_interval = (health.next_keepalive_interval * 2).min(PATH_KEEPALIVE_INTERVAL_MAX);
+                    health.state = match health.state {
+                        PathState::Good | PathState::Untested => PathState::TimeoutAwaitingPong,
+                        PathState::TimeoutAwaitingPong if health.consecutive_keepalive_failures >= PATH_WAS_GOOD_FAILURE_THRESHOLD => PathState::WasGood,
+                        PathState::WasGood => PathState::Timeout,
+                        other => other,
+                    };
+                }
                 self.last_send_time_ticks.store(time_ticks, Ordering::Relaxed);
                 PathServiceResult::NeedsKeepalive
             } else {
@@ -102,11 +441,309 @@ impl<SI: SystemInterface> Path<SI> {
         } else if (time_ticks - self.create_time_ticks) < PATH_EXPIRATION_TIME {
             PathServiceResult::Ok
         } else {
+            self.health.lock().state = PathState::Timeout;
             PathServiceResult::Dead
         }
     }
 }
 
+/// Width in bits of the sliding anti-replay bitmap, stored as an array of `u64` words.
+/// One leading 64-bit word is treated as redundant (it's always clear immediately after
+/// the window advances past it), giving an effective window of 1984 counters.
+const ANTI_REPLAY_WINDOW_BITS: u64 = 2048;
+const ANTI_REPLAY_WINDOW_SIZE: u64 = ANTI_REPLAY_WINDOW_BITS - 64;
+const ANTI_REPLAY_WINDOW_WORDS: usize = (ANTI_REPLAY_WINDOW_BITS / 64) as usize;
+
+/// A classic WireGuard-style sliding-bitmap replay filter keyed on a monotonically intended
+/// 64-bit counter (here, a packet's `PacketId`). Guards a `Path` against an attacker capturing
+/// and replaying a previously valid packet.
+struct AntiReplayWindow {
+    highest: u64,
+    initialized: bool,
+    bitmap: [u64; ANTI_REPLAY_WINDOW_WORDS],
+}
+
+impl AntiReplayWindow {
+    #[inline(always)]
+    fn new() -> Self {
+        Self { highest: 0, initialized: false, bitmap: [0u64; ANTI_REPLAY_WINDOW_WORDS] }
+    }
+
+    /// Returns true if `counter` is new and should be accepted, false if it's a replay or
+    /// has fallen off the trailing edge of the window.
+    fn check_and_update(&mut self, counter: u64) -> bool {
+        if !self.initialized {
+            // An uninitialized path has nothing to replay against yet: accept unconditionally.
+            self.initialized = true;
+            self.highest = counter;
+            self.set_bit(counter);
+            return true;
+        }
+
+        if counter + ANTI_REPLAY_WINDOW_SIZE <= self.highest {
+            // Too far behind the trailing edge of the window to have a bit allocated for it.
+            return false;
+        }
+
+        if counter > self.highest {
+            // Advance the window, clearing only the newly exposed blocks rather than the
+            // whole bitmap. Whether to do a full clear is decided by the actual counter
+            // distance, not by how many blocks that maps to: a jump of a full window or more
+            // clears everything, and any shorter jump spans at most WORDS - 1 blocks, so the
+            // partial clear below never reaches the slot holding the current `highest`.
+            let old_block = self.highest / 64;
+            if (counter - self.highest) >= ANTI_REPLAY_WINDOW_SIZE {
+                self.bitmap = [0u64; ANTI_REPLAY_WINDOW_WORDS];
+            } else {
+                let new_block = counter / 64;
+                // Cap at WORDS - 1: the block that holds the current `highest` must never be
+                // among the ones cleared here, since the whole point of reserving a redundant
+                // block is to guarantee it's never the same slot as a block we're about to
+                // expose. Capping this way preserves that guarantee regardless of alignment.
+                let advance = new_block.saturating_sub(old_block).min(ANTI_REPLAY_WINDOW_WORDS as u64 - 1);
+                for i in 1..=advance {
+                    let idx = ((old_block + i) % (ANTI_REPLAY_WINDOW_WORDS as u64)) as usize;
+                    self.bitmap[idx] = 0;
+                }
+            }
+            self.highest = counter;
+            self.set_bit(counter);
+            true
+        } else {
+            let word = ((counter / 64) % (ANTI_REPLAY_WINDOW_WORDS as u64)) as usize;
+            let bit = 1u64 << (counter % 64);
+            if (self.bitmap[word] & bit) != 0 {
+                false
+            } else {
+                self.bitmap[word] |= bit;
+                true
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn set_bit(&mut self, counter: u64) {
+        let word = ((counter / 64) % (ANTI_REPLAY_WINDOW_WORDS as u64)) as usize;
+        self.bitmap[word] |= 1u64 << (counter % 64);
+    }
+}
+
+#[cfg(test)]
+mod anti_replay_window_tests {
+    use super::*;
+
+    #[test]
+    fn first_packet_always_accepted() {
+        let mut w = AntiReplayWindow::new();
+        assert!(w.check_and_update(12345));
+    }
+
+    #[test]
+    fn exact_duplicate_is_rejected() {
+        let mut w = AntiReplayWindow::new();
+        assert!(w.check_and_update(100));
+        assert!(!w.check_and_update(100));
+    }
+
+    #[test]
+    fn counter_behind_the_window_is_rejected_as_too_old() {
+        let mut w = AntiReplayWindow::new();
+        assert!(w.check_and_update(10_000));
+        assert!(!w.check_and_update(10_000 - ANTI_REPLAY_WINDOW_SIZE));
+    }
+
+    #[test]
+    fn out_of_order_counter_still_within_window_is_accepted_once() {
+        let mut w = AntiReplayWindow::new();
+        assert!(w.check_and_update(1000));
+        assert!(w.check_and_update(990));
+        assert!(!w.check_and_update(990));
+    }
+
+    /// Pins the exact bypass the maintainer found: a forward jump whose *block* distance
+    /// rounds up to a full wrap of the circular bitmap must never leave the counter the
+    /// window advanced from accepted as valid again -- whether it ends up rejected because
+    /// it's still flagged as seen or because the advance pushed it out of the window
+    /// entirely, it must never be accepted a second time.
+    #[test]
+    fn forward_jump_whose_block_count_rounds_up_to_a_full_wrap_does_not_admit_a_replay() {
+        let mut w = AntiReplayWindow::new();
+        assert!(w.check_and_update(4080));
+        // 2000 < the old (buggy) threshold of 2016, which is exactly the gap that used to
+        // trip the block-count-based full-clear shortcut and erase the bit for 4080.
+        let gap = 2000;
+        assert!(w.check_and_update(4080 + gap));
+        assert!(!w.check_and_update(4080), "replay of a counter the window has already advanced past must still be rejected");
+    }
+
+    #[test]
+    fn forward_jump_past_the_window_clears_old_counters() {
+        let mut w = AntiReplayWindow::new();
+        assert!(w.check_and_update(1000));
+        // A jump far enough that the old counter is now genuinely out of the window should
+        // both succeed and make the old counter rejected as too old (not merely as a replay).
+        let far = 1000 + ANTI_REPLAY_WINDOW_SIZE * 4;
+        assert!(w.check_and_update(far));
+        assert!(!w.check_and_update(1000));
+    }
+}
+
+/// A `HashMap<PacketId, FragmentedPacket>` paired with a min-heap of `(expiration_tick,
+/// packet_id)` so that expiring and evicting entries is O(log n) amortized instead of the
+/// O(n) linear scan/sort this replaces. The heap uses lazy deletion: an entry is only
+/// trustworthy if it still matches the map's current copy of that packet's timestamp, since
+/// a completed or evicted packet leaves a stale heap entry behind rather than being removed
+/// from the heap directly.
+struct FragmentReassemblyTable {
+    map: HashMap<PacketId, FragmentedPacket, PacketIdHasher>,
+    expiry_heap: BinaryHeap<Reverse<(i64, PacketId)>>,
+}
+
+impl FragmentReassemblyTable {
+    fn new(hash_seed: u64) -> Self {
+        Self { map: HashMap::with_capacity_and_hasher(4, PacketIdHasher(hash_seed)), expiry_heap: BinaryHeap::new() }
+    }
+
+    #[inline(always)]
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[inline(always)]
+    fn contains_key(&self, packet_id: &PacketId) -> bool {
+        self.map.contains_key(packet_id)
+    }
+
+    #[inline(always)]
+    fn remove(&mut self, packet_id: &PacketId) -> Option<FragmentedPacket> {
+        self.map.remove(packet_id)
+    }
+
+    /// Drop heap entries sitting at the top that no longer correspond to a live map entry,
+    /// i.e. packets that have since completed or been evicted.
+    fn prune_stale_heap_top(&mut self) {
+        while let Some(Reverse((expires_at, packet_id))) = self.expiry_heap.peek() {
+            match self.map.get(packet_id) {
+                Some(frag) if frag.ts_ticks + packet_constants::FRAGMENT_EXPIRATION == *expires_at => break,
+                _ => {
+                    self.expiry_heap.pop();
+                }
+            }
+        }
+    }
+
+    fn get_or_insert(&mut self, packet_id: PacketId, time_ticks: i64) -> &mut FragmentedPacket {
+        if !self.map.contains_key(&packet_id) {
+            self.expiry_heap.push(Reverse((time_ticks + packet_constants::FRAGMENT_EXPIRATION, packet_id)));
+        }
+        self.map.entry(packet_id).or_insert_with(|| FragmentedPacket::new(time_ticks))
+    }
+
+    /// Remove every entry whose expiration tick has passed.
+    fn expire(&mut self, time_ticks: i64) {
+        loop {
+            self.prune_stale_heap_top();
+            match self.expiry_heap.peek() {
+                Some(Reverse((expires_at, _))) if *expires_at <= time_ticks => {
+                    if let Some(Reverse((_, packet_id))) = self.expiry_heap.pop() {
+                        self.map.remove(&packet_id);
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Evict up to `count` of the oldest (earliest-expiring) entries. Used when the table has
+    /// grown past the DOS sanity cap, regardless of whether those entries have technically
+    /// expired yet.
+    fn evict_oldest(&mut self, count: usize) {
+        let mut evicted = 0;
+        while evicted < count {
+            self.prune_stale_heap_top();
+            if let Some(Reverse((_, packet_id))) = self.expiry_heap.pop() {
+                if self.map.remove(&packet_id).is_some() {
+                    evicted += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod fragment_reassembly_table_tests {
+    use super::*;
+
+    #[test]
+    fn expire_removes_entries_at_the_fragment_expiration_boundary() {
+        let mut t = FragmentReassemblyTable::new(0);
+        t.get_or_insert(1, 0);
+        assert_eq!(t.len(), 1);
+
+        // Still not expired the instant before the boundary.
+        t.expire(packet_constants::FRAGMENT_EXPIRATION - 1);
+        assert!(t.contains_key(&1));
+
+        // Expired once time_ticks reaches ts_ticks + FRAGMENT_EXPIRATION.
+        t.expire(packet_constants::FRAGMENT_EXPIRATION);
+        assert!(!t.contains_key(&1));
+        assert_eq!(t.len(), 0);
+    }
+
+    #[test]
+    fn expire_leaves_entries_inserted_later_alone() {
+        let mut t = FragmentReassemblyTable::new(0);
+        t.get_or_insert(1, 0);
+        t.get_or_insert(2, packet_constants::FRAGMENT_EXPIRATION);
+
+        t.expire(packet_constants::FRAGMENT_EXPIRATION);
+        assert!(!t.contains_key(&1), "entry inserted at tick 0 must have expired");
+        assert!(t.contains_key(&2), "entry inserted at the expiration boundary must not be expired yet");
+    }
+
+    #[test]
+    fn evict_oldest_removes_earliest_expiring_entries_first_under_the_dos_cap() {
+        let mut t = FragmentReassemblyTable::new(0);
+        t.get_or_insert(1, 0);
+        t.get_or_insert(2, 10);
+        t.get_or_insert(3, 20);
+        assert_eq!(t.len(), 3);
+
+        t.evict_oldest(2);
+        assert_eq!(t.len(), 1);
+        assert!(!t.contains_key(&1));
+        assert!(!t.contains_key(&2));
+        assert!(t.contains_key(&3), "the most recently inserted (latest-expiring) entry should survive");
+    }
+
+    /// Pins the exact reason `prune_stale_heap_top` disambiguates by timestamp rather than by
+    /// `packet_id` alone: removing and then reinserting the same packet_id leaves a stale heap
+    /// entry for the old insertion behind, and that entry must be recognized as stale (by its
+    /// timestamp no longer matching the live map entry's) rather than mistaken for the new one.
+    #[test]
+    fn remove_then_reinsert_same_packet_id_does_not_resurrect_the_stale_heap_entry() {
+        let mut t = FragmentReassemblyTable::new(0);
+        t.get_or_insert(1, 0);
+        t.remove(&1);
+        // Reinsert the same packet_id much later, with a heap entry still sitting at the top
+        // from the first, now-removed insertion.
+        let second_insert_tick = 1_000;
+        t.get_or_insert(1, second_insert_tick);
+        assert_eq!(t.len(), 1);
+
+        // Expiring at the *first* insertion's expiration tick must not evict the second
+        // insertion: the stale heap entry should be pruned, not mistaken for a live expiry.
+        t.expire(packet_constants::FRAGMENT_EXPIRATION);
+        assert!(t.contains_key(&1), "reinserted packet_id must survive the original insertion's expiration tick");
+
+        // It should still expire correctly at its own, later expiration tick.
+        t.expire(second_insert_tick + packet_constants::FRAGMENT_EXPIRATION);
+        assert!(!t.contains_key(&1));
+    }
+}
+
 #[repr(transparent)]
 struct PacketIdHasher(u64);
 